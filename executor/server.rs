@@ -12,17 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use actix_web::{middleware::Logger, web, App, Error, HttpResponse, HttpServer};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, Message, StreamHandler};
+use actix_web::{middleware::Logger, web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web_actors::ws;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashSet, HashMap};
 use std::env;
 use std::path::Path;
+use std::process::Stdio;
 use std::time::{Duration, SystemTime};
 use tempfile::TempDir;
 use tokio::fs::{self, OpenOptions};
-use tokio::io::{AsyncWriteExt, AsyncBufReadExt};
-use tokio::process::Command;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::process::{Child, ChildStderr, ChildStdout, Command};
 use std::os::unix::fs::MetadataExt;
 use std::time::UNIX_EPOCH;
 
@@ -31,6 +34,16 @@ struct ExecuteRequest {
     source_code: String,
     timeout: Option<u64>,
     env: Option<HashMap<String, String>>,
+    /// Optional pinned constraints, e.g. `{"numpy": ">=1.24,<2"}`. When
+    /// present, each dependency is resolved to an exact version, its wheel
+    /// is downloaded and checksummed, and the result is recorded in
+    /// `ExecuteResult.lock` so the run is reproducible.
+    deps: Option<HashMap<String, String>>,
+    /// When set, `source_code` runs in the long-lived interpreter for this
+    /// id instead of a fresh one-shot process, so variables, imports and
+    /// installed deps from earlier calls stay bound. Falls back to the
+    /// ephemeral behavior above when omitted.
+    session_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -38,9 +51,36 @@ struct ExecuteResult {
     stdout: String,
     stderr: String,
     exit_code: i32,
-    files: Vec<String>,
+    files: Vec<FileInfo>,
+    lock: HashMap<String, ResolvedDep>,
 }
 
+/// An exactly-resolved dependency: the version selected to satisfy the
+/// request's `VersionReq` and the SHA-256 of the wheel that was verified
+/// before install.
+#[derive(Serialize, Clone)]
+struct ResolvedDep {
+    version: String,
+    sha256: String,
+}
+
+/// A changed workspace file, content-addressed by its SHA-256 digest so
+/// callers can dedup identical artifacts across runs and fetch the exact
+/// bytes later via `GET /blob/{sha256}` even if the workspace path is
+/// subsequently overwritten.
+#[derive(Serialize, Clone)]
+struct FileInfo {
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+// Digest last reported for each workspace path, so `get_changed_files` can
+// skip re-reporting a file whose content hasn't actually changed even if
+// its ctime was bumped (e.g. a rewrite-with-same-bytes).
+static KNOWN_DIGESTS: std::sync::LazyLock<std::sync::Mutex<HashMap<String, String>>> =
+    std::sync::LazyLock::new(|| std::sync::Mutex::new(HashMap::new()));
+
 static REQUIREMENTS: std::sync::LazyLock<HashSet<String>> = std::sync::LazyLock::new(|| {
     tokio::runtime::Runtime::new().unwrap().block_on(async {
         let mut requirements = HashSet::new();
@@ -87,15 +127,168 @@ async fn upload_file(
     Ok(HttpResponse::NoContent().finish())
 }
 
-async fn download_file(path: web::Path<String>) -> Result<HttpResponse, Error> {
+// Maps a filename's extension to a MIME type, the way the `get_file_type`
+// table in the srv server does, so browsers/agents get a real
+// `Content-Type` instead of a blanket octet-stream.
+fn get_file_type(filename: &str) -> &'static str {
+    match Path::new(filename).extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase().as_str() {
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "html" | "htm" => "text/html",
+        "py" => "text/x-python",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+// Parses a single-range `bytes=start-end` header value (the only form we
+// need to support to let a client resume an interrupted download).
+fn parse_range_header(value: &str, file_size: u64) -> Option<(u64, u64)> {
+    let spec = value.strip_prefix("bytes=")?;
+    let (start, end) = spec.split_once('-')?;
+    let start: u64 = start.parse().ok()?;
+    let end: u64 = if end.is_empty() { file_size.saturating_sub(1) } else { end.parse().ok()? };
+    Some((start, end))
+}
+
+async fn download_file(req: HttpRequest, path: web::Path<String>) -> Result<HttpResponse, Error> {
+    let workspace = env::var("APP_WORKSPACE").unwrap_or_else(|_| "/workspace".to_string());
+    let file_path = format!("{}/{}", workspace, path);
+    let mut file = tokio::fs::File::open(&file_path).await?;
+    let file_size = file.metadata().await?.len();
+
+    let filename = Path::new(&file_path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("download")
+        .to_string();
+
+    let range = req
+        .headers()
+        .get(actix_web::http::header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_range_header(value, file_size));
+
+    let (start, end, content_length) = match range {
+        Some((start, end)) if start <= end && start < file_size => {
+            let end = end.min(file_size.saturating_sub(1));
+            (start, end, end + 1 - start)
+        }
+        Some(_) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header(("Content-Range", format!("bytes */{file_size}")))
+                .finish());
+        }
+        // Not derived from `end - start + 1`: for a zero-length file that
+        // underflows to 1, claiming a byte that the empty body never sends.
+        None => (0, file_size.saturating_sub(1), file_size),
+    };
+
+    if start > 0 {
+        file.seek(std::io::SeekFrom::Start(start)).await?;
+    }
+    let body = tokio_util::io::ReaderStream::new(file.take(content_length));
+
+    let mut response = if range.is_some() {
+        HttpResponse::PartialContent()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .content_type(get_file_type(&filename))
+        .insert_header(("Content-Length", content_length.to_string()))
+        .insert_header(("Content-Disposition", content_disposition(&filename)));
+    if range.is_some() {
+        response.insert_header(("Content-Range", format!("bytes {start}-{end}/{file_size}")));
+    }
+
+    Ok(response.streaming(body))
+}
+
+// `filename` comes straight from a path segment a client chose when it
+// called `upload_file`, so it can contain `"`, CR/LF, or other bytes that
+// would let it break out of the quoted `filename` parameter and inject
+// extra headers. Strip quotes/control characters for the legacy `filename`
+// fallback, and additionally emit an RFC 5987 `filename*` with the name
+// percent-encoded so non-ASCII/sanitized names still round-trip exactly.
+fn content_disposition(filename: &str) -> String {
+    let ascii_fallback: String = filename
+        .chars()
+        .filter(|c| c.is_ascii() && !c.is_control() && *c != '"' && *c != '\\')
+        .collect();
+    let ascii_fallback = if ascii_fallback.is_empty() { "download".to_string() } else { ascii_fallback };
+
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_'
+            | b'`' | b'|' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    format!("attachment; filename=\"{ascii_fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+fn blob_store_dir(workspace: &str) -> String {
+    format!("{}/.cas", workspace)
+}
+
+// Streams the file through SHA-256 in ~16 KiB chunks so hashing a large
+// result artifact doesn't require loading it into memory.
+async fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
+
+// Copies the file into the content-addressed blob store so it can still be
+// fetched via `GET /blob/{sha256}` after the workspace path is overwritten.
+// A no-op if we already have a blob with this digest.
+async fn store_blob(workspace: &str, sha256: &str, source: &Path) -> std::io::Result<()> {
+    let dir = blob_store_dir(workspace);
+    fs::create_dir_all(&dir).await?;
+    let dest = format!("{}/{}", dir, sha256);
+    if fs::metadata(&dest).await.is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, &dest).await.map(|_| ())
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+async fn download_blob(sha256: web::Path<String>) -> Result<HttpResponse, Error> {
+    if !is_sha256_hex(&sha256) {
+        return Err(actix_web::error::ErrorBadRequest("invalid sha256 digest"));
+    }
     let workspace = env::var("APP_WORKSPACE").unwrap_or_else(|_| "/workspace".to_string());
-    let file = tokio::fs::File::open(format!("{}/{}", workspace, path)).await?;
+    let file = tokio::fs::File::open(format!("{}/{}", blob_store_dir(&workspace), sha256)).await?;
     Ok(HttpResponse::Ok()
         .content_type("application/octet-stream")
         .streaming(tokio_util::io::ReaderStream::new(file)))
 }
 
-async fn get_changed_files(dir: &str, since: SystemTime) -> Vec<String> {
+async fn get_changed_files(dir: &str, since: SystemTime) -> Vec<FileInfo> {
     let mut changed_files = Vec::new();
     let mut read_dir = fs::read_dir(dir).await.unwrap();
     while let Some(entry) = read_dir.next_entry().await.unwrap() {
@@ -108,20 +301,200 @@ async fn get_changed_files(dir: &str, since: SystemTime) -> Vec<String> {
             let ctime_nanos = metadata.ctime_nsec();
             let change_time = UNIX_EPOCH + Duration::new(ctime as u64, ctime_nanos as u32);
             if change_time > since {
-                if let Some(path_str) = path.to_str() {
-                    changed_files.push(path_str.to_string());
+                let Some(path_str) = path.to_str() else { continue };
+                let Ok((size, sha256)) = hash_file(&path).await else { continue };
+
+                {
+                    let mut known = KNOWN_DIGESTS.lock().unwrap();
+                    if known.get(path_str) == Some(&sha256) {
+                        continue; // content unchanged since we last reported this path
+                    }
+                    known.insert(path_str.to_string(), sha256.clone());
                 }
+
+                if let Err(err) = store_blob(dir, &sha256, &path).await {
+                    log::warn!("failed to store blob {sha256} for {path_str}: {err}");
+                }
+                changed_files.push(FileInfo { path: path_str.to_string(), size, sha256 });
             }
         }
     }
     changed_files
 }
 
-async fn execute(payload: web::Json<ExecuteRequest>) -> Result<HttpResponse, Error> {
-    let workspace = env::var("APP_WORKSPACE").unwrap_or_else(|_| "/workspace".to_string());
-    let execution_start_time = SystemTime::now();
+const PYPI_INDEX: &str = "https://pypi.org/pypi";
+
+#[derive(Deserialize)]
+struct PyPiPackageInfo {
+    releases: HashMap<String, Vec<PyPiRelease>>,
+}
+
+#[derive(Deserialize, Clone)]
+struct PyPiRelease {
+    url: String,
+    filename: String,
+    digests: PyPiDigests,
+    packagetype: String,
+}
+
+#[derive(Deserialize, Clone)]
+struct PyPiDigests {
+    sha256: String,
+}
+
+fn io_err(err: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
+}
+
+// PyPI releases aren't reliably strict semver (e.g. "1.24"), so pad short
+// version strings out to major.minor.patch before handing them to the
+// `semver` crate.
+// `semver` only understands strict major.minor.patch, but PyPI releases
+// follow PEP 440, which allows an epoch prefix (`1!2.0`), more than three
+// release segments (`2023.1.15.2`), and pre/post/dev suffixes (`2.0.0.dev1`,
+// `1.24.0.post1`). Drop the epoch and any suffix, keep the first three
+// release segments (padding out to three if there are fewer), so releases
+// using those PEP 440 extensions still get a best-effort comparable version
+// instead of being dropped from candidate selection entirely.
+fn normalize_version(version: &str) -> String {
+    let version = version.split('!').last().unwrap_or(version); // drop epoch, if any
+    let release_end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let segments: Vec<&str> = version[..release_end].split('.').filter(|s| !s.is_empty()).take(3).collect();
+    match segments.len() {
+        0 => "0.0.0".to_string(),
+        1 => format!("{}.0.0", segments[0]),
+        2 => format!("{}.0", segments.join(".")),
+        _ => segments.join("."),
+    }
+}
+
+// Fetches candidate versions from PyPI, keeps those satisfying `requirement`,
+// and selects the highest — the same resolve-then-pick-highest approach the
+// rget npm installer uses against its own registry.
+async fn resolve_dep(
+    client: &reqwest::Client,
+    name: &str,
+    requirement: &str,
+) -> std::io::Result<(ResolvedDep, PyPiRelease)> {
+    let req = semver::VersionReq::parse(requirement)
+        .map_err(|err| io_err(format!("bad version requirement for {name}: {err}")))?;
+    let info: PyPiPackageInfo = client
+        .get(format!("{PYPI_INDEX}/{name}/json"))
+        .send()
+        .await
+        .map_err(io_err)?
+        .json()
+        .await
+        .map_err(io_err)?;
+
+    let mut best: Option<(semver::Version, String, PyPiRelease)> = None;
+    for (version_str, releases) in &info.releases {
+        let Ok(version) = semver::Version::parse(&normalize_version(version_str)) else {
+            log::warn!("{name}: could not parse PyPI version {version_str:?}, skipping it for resolution");
+            continue;
+        };
+        if !req.matches(&version) {
+            continue;
+        }
+        let Some(release) = releases
+            .iter()
+            .find(|release| release.packagetype == "bdist_wheel")
+            .or_else(|| releases.first())
+        else {
+            continue;
+        };
+        // Several PyPI versions can normalize to the same semver (`2.0.0` and
+        // `2.0.0.dev1`); break such ties on the original string, preferring
+        // the shorter one, so the pick doesn't depend on HashMap order.
+        let is_better = match &best {
+            Some((best_version, best_str, _)) => {
+                (&version, std::cmp::Reverse(version_str.len()), version_str)
+                    > (best_version, std::cmp::Reverse(best_str.len()), best_str)
+            }
+            None => true,
+        };
+        if is_better {
+            best = Some((version, version_str.clone(), release.clone()));
+        }
+    }
+
+    let (_, version_str, release) = best
+        .ok_or_else(|| io_err(format!("no version of {name} satisfies {requirement}")))?;
+    Ok((
+        ResolvedDep { version: version_str, sha256: release.digests.sha256.clone() },
+        release,
+    ))
+}
+
+// Downloads the resolved wheel and verifies its SHA-256 before trusting it,
+// rather than relying solely on the transport layer.
+async fn download_and_verify(
+    client: &reqwest::Client,
+    release: &PyPiRelease,
+    dest_dir: &Path,
+) -> std::io::Result<std::path::PathBuf> {
+    use sha2::{Digest, Sha256};
+
+    let bytes = client.get(&release.url).send().await.map_err(io_err)?.bytes().await.map_err(io_err)?;
+    let mut hasher = Sha256::new();
+    hasher.update(&bytes);
+    let actual_sha256 = format!("{:x}", hasher.finalize());
+    if actual_sha256 != release.digests.sha256 {
+        return Err(io_err(format!(
+            "checksum mismatch for {}: expected {}, got {actual_sha256}",
+            release.filename, release.digests.sha256,
+        )));
+    }
+
+    let dest = dest_dir.join(&release.filename);
+    tokio::fs::write(&dest, &bytes).await?;
+    Ok(dest)
+}
+
+// Resolves each pinned dependency to an exact, checksum-verified version,
+// installs it, and records the resolution into a per-session lockfile so
+// the run is reproducible. Installs with `--no-deps`: only the requested,
+// top-level packages are resolved/verified/locked here, so letting pip
+// pull in unpinned, unverified transitive dependencies would silently
+// reintroduce the non-determinism this is meant to remove. Scripts that
+// need a pinned package's dependencies must list them explicitly in `deps`.
+async fn resolve_and_install_deps(
+    deps: &HashMap<String, String>,
+    workdir: &Path,
+) -> std::io::Result<HashMap<String, ResolvedDep>> {
+    let client = reqwest::Client::new();
+    let mut lock = HashMap::new();
+    for (name, requirement) in deps {
+        let (resolved, release) = resolve_dep(&client, name, requirement).await?;
+        let wheel_path = download_and_verify(&client, &release, workdir).await?;
+        Command::new("pip")
+            .arg("install")
+            .arg("--no-cache-dir")
+            .arg("--no-deps")
+            .arg(&wheel_path)
+            .output()
+            .await?;
+        lock.insert(name.clone(), resolved);
+    }
+
+    let lockfile = serde_json::to_vec_pretty(&lock).map_err(io_err)?;
+    tokio::fs::write(workdir.join("requirements.lock.json"), lockfile).await?;
+
+    Ok(lock)
+}
+
+// Writes the script into a fresh workdir, installs any guessed-but-missing
+// dependencies plus any pinned `deps`, and hands back a ready-to-run (but
+// not yet spawned) `xonsh` command. Shared by the buffered `/execute` route
+// and the streaming `/execute/ws` actor so both stay in sync on dependency
+// resolution.
+async fn prepare_command(
+    payload: &ExecuteRequest,
+) -> std::io::Result<(TempDir, Command, HashMap<String, ResolvedDep>)> {
     let source_dir = TempDir::new()?;
-    
+
     tokio::fs::write(source_dir.path().join("script.py"), &payload.source_code).await?;
     let guessed_deps = String::from_utf8_lossy(
         &Command::new("upm")
@@ -146,28 +519,245 @@ async fn execute(payload: web::Json<ExecuteRequest>) -> Result<HttpResponse, Err
             .await?;
     }
 
+    let lock = match &payload.deps {
+        Some(deps) if !deps.is_empty() => resolve_and_install_deps(deps, source_dir.path()).await?,
+        _ => HashMap::new(),
+    };
+
     tokio::fs::rename(source_dir.path().join("script.py"), source_dir.path().join("script.xsh")).await?;
-    
-    let timeout = Duration::from_secs(payload.timeout.unwrap_or(60));
+
     let mut cmd = Command::new("xonsh"); // TODO: manually switch between python and shell for ~80ms perf gain
     cmd.arg(source_dir.path().join("script.xsh"));
     if let Some(env) = &payload.env { cmd.envs(env); }
-    let (stdout, stderr, exit_code) = tokio::time::timeout(
-        timeout,
-        cmd.output(),
-    )
-    .await
-    .map(|r| {
-        r.map(|o| {
-            (
-                String::from_utf8_lossy(&o.stdout).to_string(),
-                String::from_utf8_lossy(&o.stderr).to_string(),
-                o.status.code().unwrap_or(-1),
+    Ok((source_dir, cmd, lock))
+}
+
+// How long an idle session worker is kept alive before the reaper tears it
+// down. Modeled on a Deno `MainWorker`'s lifecycle: the interpreter and its
+// namespace stay resident between calls, so this is the line between "the
+// agent is still thinking" and "the session is abandoned".
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+// Each worker lives behind its own mutex so the outer map lock only ever
+// guards the lookup/insert, not a whole `run()` call — otherwise every
+// session would serialize through one global lock for the duration of
+// whatever timeout the caller passed.
+static SESSIONS: std::sync::LazyLock<tokio::sync::Mutex<HashMap<String, std::sync::Arc<tokio::sync::Mutex<SessionWorker>>>>> =
+    std::sync::LazyLock::new(|| tokio::sync::Mutex::new(HashMap::new()));
+
+/// A long-lived `xonsh` interpreter backing one `session_id`. Each `run`
+/// call `source`s new code into the same process, so bindings from earlier
+/// calls stay in scope. Dropping it (reaped for idling, or via
+/// `DELETE /session/{id}`) kills the child and its workdir.
+struct SessionWorker {
+    child: Child,
+    stdin: tokio::process::ChildStdin,
+    stdout: tokio::io::Lines<tokio::io::BufReader<ChildStdout>>,
+    stderr: tokio::io::Lines<tokio::io::BufReader<ChildStderr>>,
+    workdir: TempDir,
+    step: u64,
+    last_used: SystemTime,
+    // Deps resolved so far over this session's lifetime, keyed by package
+    // name, so repeat calls with the same `deps` don't re-resolve/re-install
+    // and `execute_stateful` can hand back the full lock built up across
+    // every call, not just the most recent one.
+    deps_lock: HashMap<String, ResolvedDep>,
+}
+
+impl Drop for SessionWorker {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+    }
+}
+
+async fn read_until_sentinel<R: tokio::io::AsyncBufRead + Unpin>(
+    lines: &mut tokio::io::Lines<R>,
+    sentinel: &str,
+) -> std::io::Result<String> {
+    let mut collected = String::new();
+    while let Some(line) = lines.next_line().await? {
+        if line == sentinel {
+            return Ok(collected);
+        }
+        collected.push_str(&line);
+        collected.push('\n');
+    }
+    Ok(collected)
+}
+
+// Like `read_until_sentinel`, but the terminating line is `{sentinel}:<exit
+// code>` so the caller can tell a failed `source` (an exception was raised)
+// from a successful one instead of always reporting success.
+async fn read_until_status_sentinel<R: tokio::io::AsyncBufRead + Unpin>(
+    lines: &mut tokio::io::Lines<R>,
+    sentinel: &str,
+) -> std::io::Result<(String, i32)> {
+    let prefix = format!("{sentinel}:");
+    let mut collected = String::new();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(code) = line.strip_prefix(prefix.as_str()) {
+            return Ok((collected, code.trim().parse().unwrap_or(-1)));
+        }
+        collected.push_str(&line);
+        collected.push('\n');
+    }
+    Ok((collected, -1))
+}
+
+impl SessionWorker {
+    async fn spawn(env: Option<&HashMap<String, String>>) -> std::io::Result<Self> {
+        let workdir = TempDir::new()?;
+        let mut cmd = Command::new("xonsh");
+        cmd.arg("--no-rc").arg("-i");
+        cmd.current_dir(workdir.path());
+        if let Some(env) = env { cmd.envs(env); }
+        cmd.stdin(Stdio::piped());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = tokio::io::BufReader::new(child.stdout.take().expect("piped stdout")).lines();
+        let stderr = tokio::io::BufReader::new(child.stderr.take().expect("piped stderr")).lines();
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            workdir,
+            step: 0,
+            last_used: SystemTime::now(),
+            deps_lock: HashMap::new(),
+        })
+    }
+
+    // Resolves and installs `deps` not already in `deps_lock` into this
+    // session's still-running interpreter, merging the result into
+    // `deps_lock` so it persists across later calls on the same session.
+    async fn install_deps(&mut self, deps: &HashMap<String, String>) -> std::io::Result<()> {
+        let new_deps: HashMap<String, String> = deps
+            .iter()
+            .filter(|(name, _)| !self.deps_lock.contains_key(*name))
+            .map(|(name, requirement)| (name.clone(), requirement.clone()))
+            .collect();
+        if new_deps.is_empty() {
+            return Ok(());
+        }
+        let resolved = resolve_and_install_deps(&new_deps, self.workdir.path()).await?;
+        self.deps_lock.extend(resolved);
+        // `resolve_and_install_deps` wrote only this call's new deps; rewrite
+        // the lockfile with everything resolved over the session's lifetime.
+        let lockfile = serde_json::to_vec_pretty(&self.deps_lock).map_err(io_err)?;
+        tokio::fs::write(self.workdir.path().join("requirements.lock.json"), lockfile).await?;
+        Ok(())
+    }
+
+    // Runs `source_code` in this worker's still-alive interpreter, reading
+    // stdout/stderr up to a per-call sentinel so one call's output doesn't
+    // bleed into the next. Uses xonsh's own `source` (not a raw Python
+    // `compile`/`exec`) so xonsh-only syntax (`![cmd]`, `$(cmd)`, `@(...)`,
+    // `$VAR`) keeps working the same as it does under the ephemeral
+    // `/execute` path; it's wrapped in a try/except so a raised exception
+    // is still reported as a non-zero `exit_code`.
+    async fn run(&mut self, source_code: &str, timeout: Duration) -> std::io::Result<(String, String, i32)> {
+        self.step += 1;
+        let script_path = self.workdir.path().join(format!("step_{}.xsh", self.step));
+        tokio::fs::write(&script_path, source_code).await?;
+
+        let sentinel = format!("__bee_session_step_{}__", self.step);
+        let script_repr = format!("{:?}", script_path);
+        let command = format!(
+            "try:\n    \
+               source {script_repr}\n    \
+               print(\"{sentinel}:0\")\n\
+             except SystemExit as __bee_e:\n    \
+               print(\"{sentinel}:\" + str(__bee_e.code if isinstance(__bee_e.code, int) else 1))\n\
+             except BaseException:\n    \
+               print(\"{sentinel}:1\")\n\
+             echo \"{sentinel}\" 1>&2\n",
+        );
+        self.stdin.write_all(command.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let (stdout, stderr, exit_code) = match tokio::time::timeout(timeout, async {
+            tokio::try_join!(
+                read_until_status_sentinel(&mut self.stdout, &sentinel),
+                read_until_sentinel(&mut self.stderr, &sentinel),
             )
         })
+        .await
+        {
+            Ok(Ok(((stdout, exit_code), stderr))) => (stdout, stderr, exit_code),
+            Ok(Err(err)) => return Err(err),
+            Err(_) => (String::new(), "Execution timed out".to_string(), -1),
+        };
+
+        self.last_used = SystemTime::now();
+        Ok((stdout, stderr, exit_code))
+    }
+}
+
+async fn execute_stateful(
+    session_id: &str,
+    payload: &ExecuteRequest,
+    timeout: Duration,
+) -> std::io::Result<(String, String, i32, HashMap<String, ResolvedDep>)> {
+    let worker = {
+        let mut sessions = SESSIONS.lock().await;
+        if !sessions.contains_key(session_id) {
+            let worker = SessionWorker::spawn(payload.env.as_ref()).await?;
+            sessions.insert(session_id.to_string(), std::sync::Arc::new(tokio::sync::Mutex::new(worker)));
+        }
+        sessions.get(session_id).expect("just inserted above").clone()
+    };
+    // The map lock is released here, so other sessions (and `delete_session`
+    // / the idle reaper) aren't blocked for the duration of this run.
+    let mut worker = worker.lock().await;
+    if let Some(deps) = &payload.deps {
+        if !deps.is_empty() {
+            worker.install_deps(deps).await?;
+        }
+    }
+    let (stdout, stderr, exit_code) = worker.run(&payload.source_code, timeout).await?;
+    Ok((stdout, stderr, exit_code, worker.deps_lock.clone()))
+}
+
+async fn delete_session(session_id: web::Path<String>) -> Result<HttpResponse, Error> {
+    let removed = SESSIONS.lock().await.remove(session_id.as_str());
+    Ok(match removed {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::NotFound().finish(),
     })
-    .unwrap_or_else(|_| Ok((String::new(), "Execution timed out".to_string(), -1)))?;
-    
+}
+
+async fn execute(payload: web::Json<ExecuteRequest>) -> Result<HttpResponse, Error> {
+    let workspace = env::var("APP_WORKSPACE").unwrap_or_else(|_| "/workspace".to_string());
+    let execution_start_time = SystemTime::now();
+    let timeout = Duration::from_secs(payload.timeout.unwrap_or(60));
+
+    let (stdout, stderr, exit_code, lock) = if let Some(session_id) = &payload.session_id {
+        execute_stateful(session_id, &payload, timeout).await?
+    } else {
+        let (_source_dir, mut cmd, lock) = prepare_command(&payload).await?;
+        let (stdout, stderr, exit_code) = tokio::time::timeout(
+            timeout,
+            cmd.output(),
+        )
+        .await
+        .map(|r| {
+            r.map(|o| {
+                (
+                    String::from_utf8_lossy(&o.stdout).to_string(),
+                    String::from_utf8_lossy(&o.stderr).to_string(),
+                    o.status.code().unwrap_or(-1),
+                )
+            })
+        })
+        .unwrap_or_else(|_| Ok((String::new(), "Execution timed out".to_string(), -1)))?;
+        (stdout, stderr, exit_code, lock)
+    };
+
     let files = get_changed_files(&workspace, execution_start_time).await;
 
     Ok(HttpResponse::Ok().json(ExecuteResult {
@@ -175,20 +765,252 @@ async fn execute(payload: web::Json<ExecuteRequest>) -> Result<HttpResponse, Err
         stderr,
         exit_code,
         files,
+        lock,
     }))
 }
 
+/// Actor backing the `/execute/ws` route. The client opens the socket and
+/// sends a single JSON text frame with the `ExecuteRequest`; the actor then
+/// streams `{"stream":"stdout"|"stderr","data":...}` frames as the child
+/// produces output, finishing with a `{"exit_code":...,"files":[...]}`
+/// frame. Dropping the socket (e.g. the client disconnects) kills the
+/// in-flight child process.
+struct ExecuteSession {
+    workspace: String,
+    started_at: Option<SystemTime>,
+    lock: HashMap<String, ResolvedDep>,
+    state: WsState,
+}
+
+enum WsState {
+    WaitingForRequest,
+    Running {
+        _workdir: TempDir,
+        child: Option<Child>,
+    },
+}
+
+impl ExecuteSession {
+    fn new(workspace: String) -> Self {
+        Self {
+            workspace,
+            started_at: None,
+            lock: HashMap::new(),
+            state: WsState::WaitingForRequest,
+        }
+    }
+}
+
+impl Actor for ExecuteSession {
+    type Context = ws::WebsocketContext<Self>;
+}
+
+impl Drop for ExecuteSession {
+    fn drop(&mut self) {
+        if let WsState::Running { child: Some(child), .. } = &mut self.state {
+            let _ = child.start_kill();
+        }
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+enum ExecuteEvent {
+    Started {
+        workdir: TempDir,
+        child: Child,
+        start_time: SystemTime,
+        lock: HashMap<String, ResolvedDep>,
+    },
+    Line {
+        stream: &'static str,
+        data: String,
+    },
+    Finished {
+        exit_code: i32,
+        files: Vec<FileInfo>,
+    },
+    Failed(String),
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct PipesClosed;
+
+impl Handler<ExecuteEvent> for ExecuteSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: ExecuteEvent, ctx: &mut Self::Context) {
+        match msg {
+            ExecuteEvent::Started { workdir, child, start_time, lock } => {
+                self.started_at = Some(start_time);
+                self.lock = lock;
+                self.state = WsState::Running { _workdir: workdir, child: Some(child) };
+            }
+            ExecuteEvent::Line { stream, data } => {
+                ctx.text(serde_json::json!({ "stream": stream, "data": data }).to_string());
+            }
+            ExecuteEvent::Finished { exit_code, files } => {
+                ctx.text(serde_json::json!({ "exit_code": exit_code, "files": files, "lock": self.lock.clone() }).to_string());
+                ctx.stop();
+            }
+            ExecuteEvent::Failed(message) => {
+                ctx.text(serde_json::json!({ "stream": "stderr", "data": message }).to_string());
+                ctx.text(serde_json::json!({ "exit_code": -1, "files": Vec::<FileInfo>::new(), "lock": self.lock.clone() }).to_string());
+                ctx.stop();
+            }
+        }
+    }
+}
+
+impl Handler<PipesClosed> for ExecuteSession {
+    type Result = ();
+
+    fn handle(&mut self, _msg: PipesClosed, ctx: &mut Self::Context) {
+        let child = match &mut self.state {
+            WsState::Running { child, .. } => child.take(),
+            WsState::WaitingForRequest => None,
+        };
+        let Some(mut child) = child else { return };
+        let Some(start_time) = self.started_at else { return };
+        let workspace = self.workspace.clone();
+        let addr = ctx.address();
+        actix::spawn(async move {
+            let exit_code = child
+                .wait()
+                .await
+                .map(|status| status.code().unwrap_or(-1))
+                .unwrap_or(-1);
+            let files = get_changed_files(&workspace, start_time).await;
+            addr.do_send(ExecuteEvent::Finished { exit_code, files });
+        });
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ExecuteSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Text(text)) => {
+                if !matches!(self.state, WsState::WaitingForRequest) {
+                    return; // the session only accepts one execute request
+                }
+                let request: ExecuteRequest = match serde_json::from_str(&text) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        ctx.text(serde_json::json!({ "stream": "stderr", "data": format!("invalid request: {err}") }).to_string());
+                        ctx.stop();
+                        return;
+                    }
+                };
+                if request.session_id.is_some() {
+                    // `prepare_command` only knows how to build an ephemeral
+                    // process; it has no path to a `SessionWorker`, whose
+                    // stdout/stderr aren't piped straight from a `Child` the
+                    // way `stream_pipes` expects. Reject rather than
+                    // silently falling back to a fresh process per call.
+                    ctx.text(serde_json::json!({ "stream": "stderr", "data": "session_id is not supported over /execute/ws; use /execute" }).to_string());
+                    ctx.stop();
+                    return;
+                }
+                let addr = ctx.address();
+                actix::spawn(async move {
+                    let start_time = SystemTime::now();
+                    match prepare_command(&request).await {
+                        Ok((workdir, mut cmd, lock)) => {
+                            cmd.stdout(Stdio::piped());
+                            cmd.stderr(Stdio::piped());
+                            // If the socket closed while dependencies were
+                            // installing, `Started` is never delivered and the
+                            // child is dropped with it; make that drop kill it.
+                            cmd.kill_on_drop(true);
+                            match cmd.spawn() {
+                                Ok(mut child) => {
+                                    let stdout = child.stdout.take().expect("piped stdout");
+                                    let stderr = child.stderr.take().expect("piped stderr");
+                                    addr.do_send(ExecuteEvent::Started { workdir, child, start_time, lock });
+                                    stream_pipes(stdout, stderr, addr).await;
+                                }
+                                Err(err) => addr.do_send(ExecuteEvent::Failed(err.to_string())),
+                            }
+                        }
+                        Err(err) => addr.do_send(ExecuteEvent::Failed(err.to_string())),
+                    }
+                });
+            }
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Forwards each stdout/stderr line to the actor as it arrives, then signals
+// `PipesClosed` once both pipes have hit EOF so the actor can reap the
+// child and report its exit code.
+async fn stream_pipes(stdout: ChildStdout, stderr: ChildStderr, addr: Addr<ExecuteSession>) {
+    let mut stdout_lines = tokio::io::BufReader::new(stdout).lines();
+    let mut stderr_lines = tokio::io::BufReader::new(stderr).lines();
+    let mut stdout_open = true;
+    let mut stderr_open = true;
+
+    while stdout_open || stderr_open {
+        tokio::select! {
+            line = stdout_lines.next_line(), if stdout_open => match line {
+                Ok(Some(data)) => addr.do_send(ExecuteEvent::Line { stream: "stdout", data }),
+                _ => stdout_open = false,
+            },
+            line = stderr_lines.next_line(), if stderr_open => match line {
+                Ok(Some(data)) => addr.do_send(ExecuteEvent::Line { stream: "stderr", data }),
+                _ => stderr_open = false,
+            },
+        }
+    }
+
+    addr.do_send(PipesClosed);
+}
+
+async fn execute_ws(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+    let workspace = env::var("APP_WORKSPACE").unwrap_or_else(|_| "/workspace".to_string());
+    ws::start(ExecuteSession::new(workspace), &req, stream)
+}
+
+// Periodically evicts sessions that have had no activity for
+// `SESSION_IDLE_TIMEOUT`, dropping their `SessionWorker` (which kills the
+// child and cleans up its workdir).
+async fn reap_idle_sessions() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        SESSIONS.lock().await.retain(|_, worker| {
+            // A worker that's mid-run isn't idle, regardless of its
+            // `last_used`; skip it rather than blocking on its lock.
+            match worker.try_lock() {
+                Ok(worker) => worker.last_used.elapsed().map(|idle| idle < SESSION_IDLE_TIMEOUT).unwrap_or(true),
+                Err(_) => true,
+            }
+        });
+    }
+}
+
 #[actix_web::main]
 async fn web() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
     let listen_addr = env::var("APP_LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8000".to_string());
 
+    tokio::spawn(reap_idle_sessions());
+
     HttpServer::new(|| {
         App::new()
             .wrap(Logger::default())
             .route("/workspace/{path:.*}", web::put().to(upload_file))
             .route("/workspace/{path:.*}", web::get().to(download_file))
             .route("/execute", web::post().to(execute))
+            .route("/execute/ws", web::get().to(execute_ws))
+            .route("/blob/{sha256}", web::get().to(download_blob))
+            .route("/session/{id}", web::delete().to(delete_session))
     })
     .bind(&listen_addr)?
     .run()